@@ -1,6 +1,8 @@
 use std::cmp::max;
+use std::fmt;
 use std::iter;
-use std::io::Write;
+use std::io::{self, Write};
+use std::ops::{Deref, DerefMut};
 use std::string::FromUtf8Error;
 
 const DEFAULT_CAPACITY: usize = 1024;
@@ -11,81 +13,298 @@ const LINE_ENDING: &'static [u8] = b"\r\n";
 #[cfg(not(windows))]
 const LINE_ENDING: &'static [u8] = b"\n";
 
-/// This is a growable string builder.
+/// This is a growable string builder that wraps an arbitrary [`std::io::Write`]
+/// sink, injecting indentation as lines are emitted.
 #[derive(Debug)]
-pub struct IndentedTextWriter {
-    inner: Vec<u8>,
+pub struct IndentedTextWriter<W: Write> {
+    out: W,
     tab_string: Vec<u8>,
-    indent_level: i32,
+    /// The prebuilt indentation prefix for the current level. It grows by
+    /// `tab_string` on `indent()` and shrinks by `tab_string.len()` on
+    /// `unindent()`, so emitting it is a single `write_all`.
+    indentation: Vec<u8>,
     tabs_pending: bool,
     indent_begin: u8,
-    indent_end: u8
+    indent_end: u8,
+    /// Optional lexing rules. When set, `write_line` only counts braces that
+    /// fall outside string/char literals and line comments.
+    syntax: Option<LanguageSyntax>
 }
 
-impl Default for IndentedTextWriter {
-    fn default() -> IndentedTextWriter {
-        let inner = Vec::with_capacity(DEFAULT_CAPACITY);
+/// Lexing rules used to skip braces that appear inside string literals, char
+/// literals, or line comments when balancing indentation.
+#[derive(Debug, Clone)]
+pub struct LanguageSyntax {
+    /// Byte that opens and closes a string literal (e.g. `"`).
+    pub string_quote: u8,
+    /// Byte that opens and closes a char literal (e.g. `'`).
+    pub char_quote: u8,
+    /// Escape byte that neutralises the following quote (e.g. `\`).
+    pub escape: u8,
+    /// Prefix that starts a line comment running to end of line (e.g. `//`).
+    pub line_comment: Vec<u8>,
+}
+
+impl LanguageSyntax {
+    /// Syntax for C-like languages: double-quoted strings, single-quoted
+    /// chars, backslash escapes, and `//` line comments. A `'` only opens a
+    /// char literal when it is immediately closed (`'x'`, `'\n'`), so Rust
+    /// lifetimes such as `'a` don't accidentally swallow the rest of the line.
+    pub fn c_like() -> LanguageSyntax {
+        LanguageSyntax {
+            string_quote: b'"',
+            char_quote: b'\'',
+            escape: b'\\',
+            line_comment: b"//".to_vec(),
+        }
+    }
+}
+
+/// Decide whether the `char_quote` byte at `i` actually opens a char literal
+/// rather than, say, a Rust lifetime (`'a`). A char literal is either an escape
+/// (`'\n'`) or a single byte followed by the closing quote (`'x'`); anything
+/// else (a lone `'` with no nearby closing quote) is left in `Normal` so it does
+/// not swallow later braces.
+fn is_char_literal(bytes: &[u8], i: usize, syntax: &LanguageSyntax) -> bool {
+    bytes.get(i + 1) == Some(&syntax.escape) || bytes.get(i + 2) == Some(&syntax.char_quote)
+}
+
+/// Internal state while scanning a line for structural braces.
+enum ScanState {
+    Normal,
+    InString,
+    InChar,
+    InComment,
+    /// Inside a literal, having just seen the escape byte. The flag records
+    /// whether we were in a string (`true`) or char (`false`) literal.
+    AfterEscape(bool),
+}
+
+/// How a single indentation step is rendered.
+#[derive(Debug, Clone)]
+pub enum IndentConfig {
+    /// A single tab character per indent level.
+    Tab,
+    /// `n` spaces per indent level.
+    Space(usize),
+}
+
+impl IndentConfig {
+    /// The byte sequence emitted for one level of indentation.
+    fn tab_string(&self) -> Vec<u8> {
+        match self {
+            IndentConfig::Tab => b"\t".to_vec(),
+            IndentConfig::Space(n) => vec![b' '; *n],
+        }
+    }
+}
+
+impl Default for IndentedTextWriter<Vec<u8>> {
+    fn default() -> IndentedTextWriter<Vec<u8>> {
+        let out = Vec::with_capacity(DEFAULT_CAPACITY);
         let tab_string = " ".to_bytes();
-        let indent_level = 0;
         let tabs_pending = false;
         let indent_begin = '{'.to_bytes()[0];
         let indent_end = '}'.to_bytes()[0];
         IndentedTextWriter {
-            inner,
+            out,
             tab_string,
-            indent_level,
+            indentation: Vec::new(),
             tabs_pending,
             indent_begin,
-            indent_end
+            indent_end,
+            syntax: None
         }
     }
 }
 
-impl IndentedTextWriter {
-    /// Return a new `IndentedTextWriter` with an initial capacity.
-    pub fn new(tab_string: &str, size: usize, indent_begin:char, indent_end:char) -> IndentedTextWriter {
-        let inner = Vec::with_capacity(size);
+impl IndentedTextWriter<Vec<u8>> {
+    /// Return a new `IndentedTextWriter` with an initial capacity, buffering
+    /// into an owned `Vec<u8>`.
+    pub fn new(tab_string: &str, size: usize, indent_begin:char, indent_end:char) -> IndentedTextWriter<Vec<u8>> {
+        let out = Vec::with_capacity(size);
         IndentedTextWriter {
-            inner,
+            out,
             tab_string: tab_string.to_bytes(),
-            indent_level: 0,
+            indentation: Vec::new(),
             tabs_pending: false,
             indent_begin: indent_begin.to_bytes()[0],
             indent_end: indent_end.to_bytes()[0],
+            syntax: None,
         }
     }
-    fn output_tabs(&mut self) {
-        if self.tabs_pending {
-            for _ in 1..=self.indent_level {
-                self.inner.write_all(&self.tab_string).unwrap()
+}
+
+impl<W: Write> IndentedTextWriter<W> {
+    /// Wrap an arbitrary [`std::io::Write`] sink so generated text can be
+    /// streamed straight to a file, socket, or stdout.
+    pub fn with_writer(out: W, tab_string: &str, indent_begin:char, indent_end:char) -> IndentedTextWriter<W> {
+        IndentedTextWriter {
+            out,
+            tab_string: tab_string.to_bytes(),
+            indentation: Vec::new(),
+            tabs_pending: false,
+            indent_begin: indent_begin.to_bytes()[0],
+            indent_end: indent_end.to_bytes()[0],
+            syntax: None,
+        }
+    }
+
+    /// Wrap a sink, choosing the indentation step from an [`IndentConfig`]
+    /// (a tab or a fixed number of spaces) so the prefix math stays correct.
+    pub fn with_config(out: W, config: IndentConfig, indent_begin:char, indent_end:char) -> IndentedTextWriter<W> {
+        IndentedTextWriter {
+            out,
+            tab_string: config.tab_string(),
+            indentation: Vec::new(),
+            tabs_pending: false,
+            indent_begin: indent_begin.to_bytes()[0],
+            indent_end: indent_end.to_bytes()[0],
+            syntax: None,
+        }
+    }
+
+    /// Consume the writer and return the underlying sink.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Borrow the underlying sink mutably.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    /// Enable bracket-aware indentation. Once a [`LanguageSyntax`] is set,
+    /// `write_line` ignores braces that appear inside string literals, char
+    /// literals, or line comments when balancing the indent level.
+    pub fn set_syntax(&mut self, syntax: LanguageSyntax) {
+        self.syntax = Some(syntax);
+    }
+
+    /// Count the structural `indent_begin`/`indent_end` bytes in `bytes`.
+    /// Without a [`LanguageSyntax`] every brace counts; with one, braces inside
+    /// string/char literals and line comments are skipped via a small state
+    /// machine over `{normal, in_string, in_char, in_comment, after_escape}`.
+    fn brace_delta(&self, bytes: &[u8]) -> (i32, i32) {
+        let syntax = match &self.syntax {
+            None => {
+                let add_inc = bytes.iter().filter(|&&x| x == self.indent_begin).count() as i32;
+                let add_dec = bytes.iter().filter(|&&x| x == self.indent_end).count() as i32;
+                return (add_inc, add_dec);
+            }
+            Some(syntax) => syntax,
+        };
+        let mut add_inc = 0;
+        let mut add_dec = 0;
+        let mut state = ScanState::Normal;
+        let mut i = 0;
+        while i < bytes.len() {
+            let b = bytes[i];
+            match state {
+                ScanState::Normal => {
+                    if !syntax.line_comment.is_empty() && bytes[i..].starts_with(&syntax.line_comment) {
+                        state = ScanState::InComment;
+                        i += syntax.line_comment.len();
+                        continue;
+                    }
+                    if b == syntax.string_quote {
+                        state = ScanState::InString;
+                    } else if b == syntax.char_quote && is_char_literal(bytes, i, syntax) {
+                        state = ScanState::InChar;
+                    } else if b == self.indent_begin {
+                        add_inc += 1;
+                    } else if b == self.indent_end {
+                        add_dec += 1;
+                    }
+                }
+                ScanState::InString => {
+                    if b == syntax.escape {
+                        state = ScanState::AfterEscape(true);
+                    } else if b == syntax.string_quote {
+                        state = ScanState::Normal;
+                    }
+                }
+                ScanState::InChar => {
+                    if b == syntax.escape {
+                        state = ScanState::AfterEscape(false);
+                    } else if b == syntax.char_quote {
+                        state = ScanState::Normal;
+                    }
+                }
+                ScanState::AfterEscape(in_string) => {
+                    state = if in_string { ScanState::InString } else { ScanState::InChar };
+                }
+                ScanState::InComment => {}
             }
+            i += 1;
+        }
+        (add_inc, add_dec)
+    }
+
+    fn output_tabs(&mut self) -> io::Result<()> {
+        if self.tabs_pending {
+            self.out.write_all(&self.indentation)?;
             self.tabs_pending = false;
         }
+        Ok(())
+    }
+
+    /// Emit `bytes` as a single indented line followed by the line ending and
+    /// re-arm `tabs_pending`, without touching the indent level.
+    fn emit_line(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.write_indented(bytes)?;
+        self.out.write_all(LINE_ENDING)?;
+        self.tabs_pending = true;
+        Ok(())
+    }
+
+    /// Forward `buf` to the sink, re-arming `tabs_pending` after every newline
+    /// so each non-empty physical line gets the current indent prefix. A
+    /// trailing newline leaves tabs pending without emitting a blank indented
+    /// line.
+    fn write_indented(&mut self, buf: &[u8]) -> io::Result<()> {
+        for chunk in buf.split_inclusive(|&b| b == b'\n') {
+            if chunk.first() != Some(&b'\n') {
+                self.output_tabs()?;
+            }
+            self.out.write_all(chunk)?;
+            if chunk.last() == Some(&b'\n') {
+                self.tabs_pending = true;
+            }
+        }
+        Ok(())
     }
     /// get tab line
     pub fn get_tab_line(&self) -> String {
-        let mut s = String::new();
-        for _ in 1..=self.indent_level {
-            s.push_str(&String::from_utf8_lossy(&self.tab_string));
-        }
-        s
+        String::from_utf8_lossy(&self.indentation).into_owned()
     }
     /// indent
     pub fn indent(&mut self) {
-        self.indent_level += 1;
+        self.indentation.extend_from_slice(&self.tab_string);
     }
     /// indent
     pub fn indents(&mut self, len: i32) {
-        self.indent_level += len;
+        if len < 0 {
+            return self.unindents(-len);
+        }
+        for _ in 0..len {
+            self.indent();
+        }
     }
     /// unindent
     pub fn unindent(&mut self) {
-        self.indent_level -= 1;
+        let new_len = self.indentation.len().saturating_sub(self.tab_string.len());
+        self.indentation.truncate(new_len);
     }
 
     /// unindent
     pub fn unindents(&mut self, size: i32) {
-        self.indent_level -= size;
+        if size < 0 {
+            return self.indents(-size);
+        }
+        for _ in 0..size {
+            self.unindent();
+        }
     }
 
     /// Add a type that can be viewed as a slice of bytes.
@@ -96,11 +315,10 @@ impl IndentedTextWriter {
     /// use indented_text_writer::IndentedTextWriter;
     ///
     /// let mut writer = IndentedTextWriter::default();
-    /// writer.write("some string");
+    /// writer.write("some string").unwrap();
     /// ```
-    pub fn write<T: ToBytes>(&mut self, buf: T) {
-        self.output_tabs();
-        self.inner.write_all(&buf.to_bytes()).unwrap()
+    pub fn write<T: ToBytes>(&mut self, buf: T) -> io::Result<()> {
+        self.write_indented(&buf.to_bytes())
     }
 
     /// Add a type that can be viewed as a slice of bytes.
@@ -111,10 +329,10 @@ impl IndentedTextWriter {
     /// use indented_text_writer::IndentedTextWriter;
     ///
     /// let mut writer = IndentedTextWriter::default();
-    /// writer.write_no_tabs("some string");
+    /// writer.write_no_tabs("some string").unwrap();
     /// ```
-    pub fn write_no_tabs<T: ToBytes>(&mut self, buf: T) {
-        self.inner.write_all(&buf.to_bytes()).unwrap()
+    pub fn write_no_tabs<T: ToBytes>(&mut self, buf: T) -> io::Result<()> {
+        self.out.write_all(&buf.to_bytes())
     }
 
     /// Add a type that can be viewed as a slice of bytes.
@@ -125,20 +343,17 @@ impl IndentedTextWriter {
     /// use indented_text_writer::IndentedTextWriter;
     ///
     /// let mut writer = IndentedTextWriter::default();
-    /// writer.write_line("some string");
+    /// writer.write_line("some string").unwrap();
     /// ```
-    pub fn write_line<T: ToBytes>(&mut self, buf: T) {
+    pub fn write_line<T: ToBytes>(&mut self, buf: T) -> io::Result<()> {
         let bytes = buf.to_bytes();
-        let add_inc = bytes.iter().filter(|&&x| x == self.indent_begin).count() as i32;
-        let add_dec = bytes.iter().filter(|&&x| x == self.indent_end).count()as i32;
+        let (add_inc, add_dec) = self.brace_delta(&bytes);
         let inc = max(add_inc - add_dec, 0);
         let dec = max(add_dec - add_inc, 0);
         self.unindents(dec);
-        self.output_tabs();
-        self.inner.write_all(&buf.to_bytes()).unwrap();
-        self.inner.write_all(LINE_ENDING).unwrap();
-        self.tabs_pending = true;
+        self.emit_line(&bytes)?;
         self.indents(inc);
+        Ok(())
     }
 
     /// Add a type that can be viewed as a slice of bytes.
@@ -148,14 +363,86 @@ impl IndentedTextWriter {
     /// ```rust
     /// use indented_text_writer::IndentedTextWriter;
     ///
-    /// let mut writer = IndentedTextWriter::new();
-    /// writer.write_line_no_tabs("some string");
+    /// let mut writer = IndentedTextWriter::new("  ", 1024, '{', '}');
+    /// writer.write_line_no_tabs("some string").unwrap();
+    /// ```
+    pub fn write_line_no_tabs<T: ToBytes>(&mut self, buf: T) -> io::Result<()> {
+        self.out.write_all(&buf.to_bytes())?;
+        self.out.write_all(LINE_ENDING)
+    }
+
+    /// Open a brace-delimited block: write `header` followed by a space and the
+    /// `indent_begin` byte (` {` by default), increment
+    /// the indent, and return a guard that emits the closing `indent_end` line
+    /// and unindents when it drops. Everything written through the guard until
+    /// it drops is one level deeper, so nested blocks can't mis-balance even on
+    /// early return.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use indented_text_writer::IndentedTextWriter;
+    ///
+    /// let mut writer = IndentedTextWriter::default();
+    /// {
+    ///     let mut block = writer.block("fn main()").unwrap();
+    ///     block.write_line("println!(\"hi\");").unwrap();
+    /// }
     /// ```
-    pub fn write_line_no_tabs<T: ToBytes>(&mut self, buf: T) {
-        self.inner.write_all(&buf.to_bytes()).unwrap();
-        self.inner.write_all(LINE_ENDING).unwrap()
+    pub fn block<T: ToBytes>(&mut self, header: T) -> io::Result<BlockGuard<'_, W>> {
+        let close = self.indent_end;
+        let mut line = header.to_bytes();
+        line.push(b' ');
+        line.push(self.indent_begin);
+        self.emit_line(&line)?;
+        self.indent();
+        Ok(BlockGuard { writer: self, close: Some(vec![close]) })
     }
 
+    /// Open an indent-only block for brace-less languages (e.g. Python): write
+    /// `header` verbatim, increment the indent, and return a guard that merely
+    /// unindents when it drops.
+    pub fn indent_block<T: ToBytes>(&mut self, header: T) -> io::Result<BlockGuard<'_, W>> {
+        self.emit_line(&header.to_bytes())?;
+        self.indent();
+        Ok(BlockGuard { writer: self, close: None })
+    }
+}
+
+/// Scope guard returned by [`IndentedTextWriter::block`] and
+/// [`IndentedTextWriter::indent_block`]. Dereferences to the underlying writer
+/// so content can be emitted through it, and closes the block on drop.
+pub struct BlockGuard<'a, W: Write> {
+    writer: &'a mut IndentedTextWriter<W>,
+    /// Bytes for the closing line (e.g. `}`), or `None` for an indent-only block.
+    close: Option<Vec<u8>>,
+}
+
+impl<'a, W: Write> Deref for BlockGuard<'a, W> {
+    type Target = IndentedTextWriter<W>;
+    fn deref(&self) -> &Self::Target {
+        self.writer
+    }
+}
+
+impl<'a, W: Write> DerefMut for BlockGuard<'a, W> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.writer
+    }
+}
+
+impl<'a, W: Write> Drop for BlockGuard<'a, W> {
+    fn drop(&mut self) {
+        self.writer.unindent();
+        if let Some(close) = self.close.take() {
+            // Drop can't propagate `io::Result`; an error from writing the
+            // closing line is discarded.
+            let _ = self.writer.emit_line(&close);
+        }
+    }
+}
+
+impl IndentedTextWriter<Vec<u8>> {
     /// Return the current length in bytes of the underlying buffer.
     ///
     /// # Example
@@ -164,11 +451,11 @@ impl IndentedTextWriter {
     /// use indented_text_writer::IndentedTextWriter;
     ///
     /// let mut writer = IndentedTextWriter::default();
-    /// writer.write("four");
+    /// writer.write("four").unwrap();
     /// assert_eq!(writer.len(), 4);
     /// ```
     pub fn len(&self) -> usize {
-        self.inner.len()
+        self.out.len()
     }
 
     /// Return a `String` of our buffer once we are done appending to it. This method consumes
@@ -180,13 +467,34 @@ impl IndentedTextWriter {
     /// use indented_text_writer::IndentedTextWriter;
     ///
     /// let mut writer = IndentedTextWriter::default();
-    /// writer.write("i am building");
-    /// writer.write(' ');
-    /// writer.write("a string");
+    /// writer.write("i am building").unwrap();
+    /// writer.write(' ').unwrap();
+    /// writer.write("a string").unwrap();
     /// assert_eq!(writer.string().unwrap(), "i am building a string");
     /// ```
     pub fn string(self) -> Result<String, FromUtf8Error> {
-        String::from_utf8(self.inner)
+        String::from_utf8(self.out)
+    }
+}
+
+/// Drive the writer through `write!`/`writeln!`, injecting indentation for
+/// each buffered line just like [`IndentedTextWriter::write`].
+impl<W: Write> Write for IndentedTextWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_indented(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Allow the writer to be used with the `core::fmt` machinery so `write!`
+/// formats indent straight into the underlying sink.
+impl<W: Write> fmt::Write for IndentedTextWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_indented(s.as_bytes()).map_err(|_| fmt::Error)
     }
 }
 
@@ -241,15 +549,79 @@ impl<'a> ToBytes for &'a [u8] {
 
 #[cfg(test)]
 mod tests {
-    use super::IndentedTextWriter;
+    use super::{IndentedTextWriter, LanguageSyntax};
 
     #[test]
     fn tests_generate_class() {
         let mut writer = IndentedTextWriter::new("  ",1024, '{', '}');
-        writer.write_line("struct Data {");
-        writer.write_line("name: String,");
-        writer.write_line("value: i32");
-        writer.write_line("}");
+        writer.write_line("struct Data {").unwrap();
+        writer.write_line("name: String,").unwrap();
+        writer.write_line("value: i32").unwrap();
+        writer.write_line("}").unwrap();
         println!("{}",writer.string().unwrap());
     }
+
+    #[test]
+    fn write_reindents_after_embedded_newline() {
+        let mut writer = IndentedTextWriter::new("  ", 1024, '{', '}');
+        writer.write_line("block {").unwrap();
+        writer.write("line1\nline2").unwrap();
+        assert_eq!(writer.string().unwrap(), "block {\n  line1\n  line2");
+    }
+
+    #[test]
+    fn ignores_braces_in_strings_and_comments() {
+        let mut writer = IndentedTextWriter::new("  ", 1024, '{', '}');
+        writer.set_syntax(LanguageSyntax::c_like());
+        writer.write_line("fn main() {").unwrap();
+        writer.write_line("println!(\"}\");").unwrap();
+        writer.write_line("// a closing } in a comment").unwrap();
+        writer.write_line("let c = '}';").unwrap();
+        writer.write_line("}").unwrap();
+        assert_eq!(
+            writer.string().unwrap(),
+            "fn main() {\n  println!(\"}\");\n  // a closing } in a comment\n  let c = '}';\n}\n"
+        );
+    }
+
+    #[test]
+    fn nested_blocks_balance_indentation() {
+        let mut writer = IndentedTextWriter::new("  ", 1024, '{', '}');
+        {
+            let mut outer = writer.block("fn main()").unwrap();
+            outer.write_line("let x = 1;").unwrap();
+            {
+                let mut inner = outer.block("if x > 0").unwrap();
+                inner.write_line("print(x);").unwrap();
+            }
+            outer.write_line("return;").unwrap();
+        }
+        assert_eq!(
+            writer.string().unwrap(),
+            "fn main() {\n  let x = 1;\n  if x > 0 {\n    print(x);\n  }\n  return;\n}\n"
+        );
+    }
+
+    #[test]
+    fn indent_block_only_indents() {
+        let mut writer = IndentedTextWriter::new("  ", 1024, '{', '}');
+        {
+            let mut block = writer.indent_block("def main():").unwrap();
+            block.write_line("pass").unwrap();
+        }
+        assert_eq!(writer.string().unwrap(), "def main():\n  pass\n");
+    }
+
+    #[test]
+    fn lifetime_quote_does_not_swallow_brace() {
+        let mut writer = IndentedTextWriter::new("  ", 1024, '{', '}');
+        writer.set_syntax(LanguageSyntax::c_like());
+        writer.write_line("impl<'a> Foo {").unwrap();
+        writer.write_line("fn bar() {}").unwrap();
+        writer.write_line("}").unwrap();
+        assert_eq!(
+            writer.string().unwrap(),
+            "impl<'a> Foo {\n  fn bar() {}\n}\n"
+        );
+    }
 }